@@ -0,0 +1,83 @@
+//! GF(2) CRC32 combination.
+//!
+//! When blocks are compressed independently and in parallel, no single running CRC32 is ever
+//! computed over the whole input the way a sequential encoder would. Each worker can still cheaply
+//! compute the CRC32 of its own block, though, and this module folds those per-block CRC32s back
+//! together, in order, into the one CRC32 a standard gzip trailer expects.
+//!
+//! This is a port of zlib's `crc32_combine`: a CRC update is linear over GF(2), so "what would
+//! `crc1` become after `len2` more zero bytes" can be expressed as a 32x32 bit matrix, built once
+//! and repeatedly squared while walking the bits of `len2`, then applied to `crc1` before XOR-ing
+//! in `crc2`.
+
+const GF2_DIM: usize = 32;
+
+/// Multiply the GF(2) vector `vec` by the matrix `mat`.
+fn gf2_matrix_times(mat: &[u32; GF2_DIM], mut vec: u32) -> u32 {
+    let mut sum = 0u32;
+    let mut i = 0;
+    while vec != 0 {
+        if vec & 1 != 0 {
+            sum ^= mat[i];
+        }
+        vec >>= 1;
+        i += 1;
+    }
+    sum
+}
+
+/// Square the GF(2) matrix `mat` into `square`.
+fn gf2_matrix_square(square: &mut [u32; GF2_DIM], mat: &[u32; GF2_DIM]) {
+    for (n, slot) in square.iter_mut().enumerate() {
+        *slot = gf2_matrix_times(mat, mat[n]);
+    }
+}
+
+/// Combine `crc1`, the CRC32 of some data of length `len1`, with `crc2`, the CRC32 of `len2` bytes
+/// that immediately follow it, into the CRC32 of the whole `len1 + len2` byte sequence.
+pub(crate) fn crc32_combine(crc1: u32, crc2: u32, len2: u64) -> u32 {
+    if len2 == 0 {
+        return crc1;
+    }
+
+    // `odd` starts out as the matrix for "one more zero bit"; `even` is then its square ("two more
+    // zero bits") and `odd` is squared again ("four more zero bits"). The first square inside the
+    // loop is thus the matrix for one zero byte, and from there each bit of `len2` selects the
+    // matrix for the corresponding power-of-two-sized run of zero bytes.
+    let mut odd = [0u32; GF2_DIM];
+    let mut even = [0u32; GF2_DIM];
+
+    odd[0] = 0xedb8_8320; // CRC-32 polynomial
+    let mut row = 1u32;
+    for slot in odd.iter_mut().skip(1) {
+        *slot = row;
+        row <<= 1;
+    }
+
+    gf2_matrix_square(&mut even, &odd);
+    gf2_matrix_square(&mut odd, &even);
+
+    let mut crc1 = crc1;
+    let mut len2 = len2;
+    loop {
+        gf2_matrix_square(&mut even, &odd);
+        if len2 & 1 != 0 {
+            crc1 = gf2_matrix_times(&even, crc1);
+        }
+        len2 >>= 1;
+        if len2 == 0 {
+            break;
+        }
+
+        gf2_matrix_square(&mut odd, &even);
+        if len2 & 1 != 0 {
+            crc1 = gf2_matrix_times(&odd, crc1);
+        }
+        len2 >>= 1;
+        if len2 == 0 {
+            break;
+        }
+    }
+
+    crc1 ^ crc2
+}
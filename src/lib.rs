@@ -8,6 +8,16 @@
 //!
 //! - Gzip: [`pgz::pargz`]
 //! - Snap: [`pgz::parsnap`]
+//! - Zstandard: [`pgz::parzstd`]
+//!
+//! Each encoding also has a parallel decompression counterpart that implements [`Read`]
+//! (e.g. [`pargz::ParGzReader`], [`parsnap::ParSnapReader`], [`parzstd::ParZstdReader`]): it scans the stream for the
+//! independent member/block boundaries written by the matching compressor, inflates them
+//! concurrently, and reassembles the decompressed bytes back into the original order. A stream
+//! containing only a single member falls back to ordinary sequential decoding.
+//!
+//! [`pgz::parzip`] builds on the same worker pool to write a standard ZIP archive, compressing
+//! many entries (and large entries split into chunks) in parallel.
 //!
 //! # References
 //!
@@ -16,10 +26,15 @@
 //!
 //! # Known Differences from Pigz
 //!
+//! These apply to the default, independent-member mode; [`pargz::ParGzBuilder::single_member`]
+//! writes one combined CRC32 instead, just like pigz, and [`pargz::ParGzBuilder::dictionary`]
+//! additionally carries a preset dictionary over between blocks, also like pigz:
+//!
 //! - Each block has an independent CRC value
 //! - There is no continual dictionary for compression, compression is per-block only. On some data
 //!   types this could lead to no compression for a given block if the block is small enough or the
-//!   data is random enough.
+//!   data is random enough ([`pargz::ParGzBuilder::min_compress_size`] mitigates this by falling
+//!   back to an uncompressed stored block instead).
 //!
 //! # Examples
 //!
@@ -42,10 +57,15 @@ use bytes::BytesMut;
 use flume::{unbounded, Receiver, Sender};
 use thiserror::Error;
 
+pub(crate) mod crc;
 #[cfg(feature = "pargz")]
 pub mod pargz;
 #[cfg(feature = "parsnap")]
 pub mod parsnap;
+#[cfg(feature = "parzip")]
+pub mod parzip;
+#[cfg(feature = "parzstd")]
+pub mod parzstd;
 
 /// 128 KB default buffer size, same as pigz
 pub(crate) const BUFSIZE: usize = 64 * (1 << 10) * 2;
@@ -60,6 +80,8 @@ pub enum GzpError {
     Io(#[from] io::Error),
     #[error("Unknown")]
     Unknown,
+    #[error("CRC32 mismatch: stream is corrupt or truncated")]
+    CrcMismatch,
 }
 
 /// A message sent from the Par writer to the compressor.
@@ -0,0 +1,925 @@
+//! Parallel gzip compression and decompression.
+//!
+//! [`ParGz`] implements [`Write`] and splits input into blocks that are compressed in parallel on
+//! a pool of worker threads. By default each block is emitted as its own independent gzip member
+//! (the simplest strategy, and the one pigz falls back to). [`ParGzBuilder::single_member`] and
+//! [`ParGzBuilder::dictionary`] instead write a single continuous gzip member, matching pigz's own
+//! output more closely. [`ParGzReader`] is the inverse: it implements [`Read`] by scanning a gzip
+//! stream for member boundaries and inflating the members in parallel, reassembling the
+//! decompressed bytes back into the original order; a stream with only one member (which is what
+//! [`ParGzBuilder::single_member`] and [`ParGzBuilder::dictionary`] both produce) is decoded
+//! sequentially instead, since there is nothing to split on.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::{Cursor, Read, Write};
+//!
+//! use gzp::pargz::{ParGz, ParGzReader};
+//!
+//! let mut par_gz = ParGz::builder(vec![]).build();
+//! par_gz.write_all(b"This is a first test line\n").unwrap();
+//! par_gz.write_all(b"This is a second test line\n").unwrap();
+//! let compressed = par_gz.finish().unwrap();
+//!
+//! let mut reader = ParGzReader::builder(Cursor::new(compressed)).build();
+//! let mut decompressed = String::new();
+//! reader.read_to_string(&mut decompressed).unwrap();
+//! assert_eq!(decompressed, "This is a first test line\nThis is a second test line\n");
+//! ```
+use std::io::{self, Read, Write};
+use std::mem;
+use std::thread::{self, JoinHandle};
+
+use bytes::{Bytes, BytesMut};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::{Compress, Compression, Crc, FlushCompress, Status};
+use flume::{unbounded, Receiver, Sender};
+
+use crate::crc::crc32_combine;
+use crate::{GzpError, Message, BUFSIZE};
+
+/// The maximum size of the preset dictionary carried over between blocks in
+/// [`ParGzBuilder::dictionary`] mode, matching zlib's 32 KiB window.
+const DICT_SIZE: usize = 32 * 1024;
+
+/// A minimal, flag-less 10 byte gzip header (`CM=8`, `FLG=0`, `MTIME=0`, `XFL=0`, `OS=unknown`),
+/// used to open the stream(s) this module writes by hand: a [`store_member`] fallback for a
+/// single independent member, or the single continuous stream written by
+/// [`ParGzBuilder::single_member`] and [`ParGzBuilder::dictionary`]. `compress_block` builds an
+/// equivalent header itself via [`GzEncoder`].
+const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff];
+
+/// Builder for [`ParGz`].
+pub struct ParGzBuilder<W> {
+    writer: W,
+    buffer_size: usize,
+    num_threads: usize,
+    compression_level: Compression,
+    dictionary: bool,
+    single_member: bool,
+    min_compress_size: usize,
+}
+
+impl<W> ParGzBuilder<W>
+where
+    W: Write + Send + 'static,
+{
+    /// Create a new builder with sane defaults.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buffer_size: BUFSIZE,
+            num_threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            compression_level: Compression::default(),
+            dictionary: false,
+            single_member: false,
+            min_compress_size: 0,
+        }
+    }
+
+    /// Set the size of the blocks that are handed off to the compressor threads.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Set the number of worker threads used to compress blocks.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads.max(1);
+        self
+    }
+
+    /// Set the gzip compression level.
+    pub fn compression_level(mut self, compression_level: Compression) -> Self {
+        self.compression_level = compression_level;
+        self
+    }
+
+    /// Write a single standard gzip member instead of one independent member per block.
+    ///
+    /// Blocks are still compressed fully independently (each worker starts from a blank deflate
+    /// window), but instead of each one closing out its own member, all but the last are flushed
+    /// with `Z_FULL_FLUSH`, which resets the compressor's window to a byte-aligned sync point
+    /// without ending the deflate stream. Concatenated together with one gzip header in front and
+    /// one combined CRC32 + ISIZE trailer at the end, the result is byte-for-byte the same kind of
+    /// stream pigz produces, and is decodable by any stock gunzip as a single member. See
+    /// [`crate::crc`] for how the per-block CRC32s are folded into the trailer's combined CRC32.
+    pub fn single_member(mut self, single_member: bool) -> Self {
+        self.single_member = single_member;
+        self
+    }
+
+    /// Carry the previous block's trailing 32 KiB of *uncompressed* input over to the next block
+    /// as a preset deflate dictionary, the way pigz does, to recover ratio lost to per-block
+    /// compression on small or low-entropy blocks. Implies [`ParGzBuilder::single_member`]: a
+    /// preset dictionary only makes sense as part of one continuous member.
+    pub fn dictionary(mut self, dictionary: bool) -> Self {
+        self.dictionary = dictionary;
+        self
+    }
+
+    /// Skip attempting to deflate blocks smaller than `min_compress_size`, writing them as
+    /// uncompressed deflate stored blocks instead. Compression is also always skipped in favor of
+    /// a stored block when deflate would have expanded the block, regardless of this setting, so
+    /// output never exceeds input by more than a few bytes per block. Useful for streams made up
+    /// of many small or already-compressed (i.e. high-entropy) blocks, where attempting
+    /// compression is pure wasted work.
+    pub fn min_compress_size(mut self, min_compress_size: usize) -> Self {
+        self.min_compress_size = min_compress_size;
+        self
+    }
+
+    /// Build the [`ParGz`] writer, spawning the compressor and writer threads.
+    pub fn build(self) -> ParGz<W> {
+        if self.dictionary || self.single_member {
+            ParGz {
+                inner: Inner::Continuous(self.build_continuous()),
+            }
+        } else {
+            ParGz {
+                inner: Inner::Independent(self.build_independent()),
+            }
+        }
+    }
+
+    fn build_independent(self) -> IndependentWriter<W> {
+        let (tx, rx) = unbounded::<Message>();
+        let (order_tx, order_rx) = unbounded::<Receiver<Result<Vec<u8>, GzpError>>>();
+
+        let mut worker_handles = Vec::with_capacity(self.num_threads);
+        for _ in 0..self.num_threads {
+            let rx = rx.clone();
+            let level = self.compression_level;
+            let min_compress_size = self.min_compress_size;
+            worker_handles.push(thread::spawn(move || {
+                for message in rx.iter() {
+                    let result = compress_block(&message.buffer, level, min_compress_size);
+                    let _ = message.oneshot.send(result);
+                }
+            }));
+        }
+
+        let mut writer = self.writer;
+        let writer_handle = thread::spawn(move || -> Result<W, GzpError> {
+            for block_rx in order_rx.iter() {
+                let block = block_rx.recv()??;
+                writer.write_all(&block)?;
+            }
+            writer.flush()?;
+            Ok(writer)
+        });
+
+        IndependentWriter {
+            sender: Some(tx),
+            order_sender: Some(order_tx),
+            worker_handles,
+            writer_handle: Some(writer_handle),
+            buffer: BytesMut::with_capacity(self.buffer_size),
+            buffer_size: self.buffer_size,
+        }
+    }
+
+    fn build_continuous(self) -> ContinuousWriter<W> {
+        let (tx, rx) = unbounded::<ContinuousMessage>();
+        let (order_tx, order_rx) = unbounded::<Receiver<Result<BlockResult, GzpError>>>();
+
+        let mut worker_handles = Vec::with_capacity(self.num_threads);
+        for _ in 0..self.num_threads {
+            let rx = rx.clone();
+            let level = self.compression_level;
+            let min_compress_size = self.min_compress_size;
+            worker_handles.push(thread::spawn(move || {
+                for message in rx.iter() {
+                    let result = compress_continuous_block(
+                        &message.buffer,
+                        message.dictionary.as_deref(),
+                        message.is_last,
+                        level,
+                        min_compress_size,
+                    );
+                    let _ = message.oneshot.send(result);
+                }
+            }));
+        }
+
+        let mut writer = self.writer;
+        let writer_handle = thread::spawn(move || -> Result<W, GzpError> {
+            writer.write_all(&GZIP_HEADER)?;
+            let mut crc = 0u32;
+            let mut len = 0u64;
+            for block_rx in order_rx.iter() {
+                let block = block_rx.recv()??;
+                writer.write_all(&block.data)?;
+                crc = crc32_combine(crc, block.crc, block.len);
+                len += block.len;
+            }
+            writer.write_all(&crc.to_le_bytes())?;
+            writer.write_all(&(len as u32).to_le_bytes())?;
+            writer.flush()?;
+            Ok(writer)
+        });
+
+        ContinuousWriter {
+            sender: Some(tx),
+            order_sender: Some(order_tx),
+            worker_handles,
+            writer_handle: Some(writer_handle),
+            buffer: BytesMut::with_capacity(self.buffer_size),
+            buffer_size: self.buffer_size,
+            use_dictionary: self.dictionary,
+            pending: None,
+            dict_for_pending: None,
+            last_tail: None,
+        }
+    }
+}
+
+/// Compress a single block into its own independent gzip member. Blocks smaller than
+/// `min_compress_size`, and blocks deflate fails to shrink, are written as a stored (uncompressed)
+/// member instead; see [`store_member`].
+fn compress_block(buffer: &[u8], level: Compression, min_compress_size: usize) -> Result<Vec<u8>, GzpError> {
+    if buffer.len() < min_compress_size {
+        return Ok(store_member(buffer));
+    }
+    let mut encoder = GzEncoder::new(Vec::with_capacity(buffer.len()), level);
+    encoder.write_all(buffer)?;
+    let compressed = encoder.finish()?;
+    if compressed.len() > buffer.len() {
+        return Ok(store_member(buffer));
+    }
+    Ok(compressed)
+}
+
+/// Wrap `buffer` in a complete gzip member using a deflate stored (uncompressed) block, for data
+/// that deflate compression would only expand.
+fn store_member(buffer: &[u8]) -> Vec<u8> {
+    let mut crc = Crc::new();
+    crc.update(buffer);
+    let mut out = Vec::with_capacity(GZIP_HEADER.len() + buffer.len() + 16);
+    out.extend_from_slice(&GZIP_HEADER);
+    out.extend_from_slice(&store_block(buffer, true));
+    out.extend_from_slice(&crc.sum().to_le_bytes());
+    out.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+    out
+}
+
+/// The largest chunk a single deflate stored block can carry, since its `LEN` field is 16 bits.
+const MAX_STORED_LEN: usize = u16::MAX as usize;
+
+/// Wrap `buffer` in one or more uncompressed deflate stored blocks (`BTYPE=00`): a header byte
+/// (`BFINAL` bit plus the two `BTYPE=00` bits, padded out to the byte boundary), `LEN` and its
+/// one's complement `NLEN` (both little-endian `u16`s), then the literal bytes. This requires the
+/// block to start at a byte boundary, which holds both for the first block of a fresh stream and
+/// for a block immediately following one closed with `Z_FULL_FLUSH`. Only the final sub-block, and
+/// only if `is_last`, sets `BFINAL`.
+fn store_block(buffer: &[u8], is_last: bool) -> Vec<u8> {
+    if buffer.is_empty() {
+        // Deflate always needs at least one block; an empty stored block still marks the stream
+        // final if asked to.
+        return vec![u8::from(is_last), 0x00, 0x00, 0xff, 0xff];
+    }
+    let num_chunks = (buffer.len() + MAX_STORED_LEN - 1) / MAX_STORED_LEN;
+    let mut out = Vec::with_capacity(buffer.len() + num_chunks * 5);
+    let mut chunks = buffer.chunks(MAX_STORED_LEN).peekable();
+    while let Some(chunk) = chunks.next() {
+        let final_block = is_last && chunks.peek().is_none();
+        out.push(u8::from(final_block));
+        let len = chunk.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out
+}
+
+/// The result of compressing one block of a [`ParGzBuilder::single_member`] /
+/// [`ParGzBuilder::dictionary`] continuous stream: the raw deflate bytes plus the CRC32 and
+/// length of the uncompressed input, needed by the writer thread to fold into the running trailer
+/// via [`crc32_combine`].
+struct BlockResult {
+    data: Vec<u8>,
+    crc: u32,
+    len: u64,
+}
+
+/// Drive `compress` over all of `input` with the given `flush`, appending output to `out`, looping
+/// until the flush has definitely been fully applied. A single `compress_vec` call isn't always
+/// enough: it can return having consumed all of `input` but before the compressor has actually
+/// finished emitting that flush's output, requiring another call (with an empty remaining-input
+/// slice) to find out. For `FlushCompress::Finish` that means looping until `Status::StreamEnd`;
+/// other flush kinds never report `StreamEnd`, so completion instead means all input was consumed
+/// and the call left spare room in `out`, i.e. it stopped because there was nothing left to flush,
+/// not because `out` ran out of space. `Status::BufError` specifically means the latter: `out` was
+/// full, not that the flush is done, so it must never be treated as a terminal state; we grow `out`
+/// and go around again instead.
+fn run_compress(
+    compress: &mut Compress,
+    input: &[u8],
+    out: &mut Vec<u8>,
+    flush: FlushCompress,
+) -> Result<(), GzpError> {
+    let base_in = compress.total_in() as usize;
+    loop {
+        let consumed = compress.total_in() as usize - base_in;
+        if out.len() == out.capacity() {
+            out.reserve(input.len().max(1024));
+        }
+        let status = compress
+            .compress_vec(&input[consumed..], out, flush)
+            .map_err(|_| GzpError::Unknown)?;
+        if status == Status::StreamEnd {
+            break;
+        }
+        let done = status != Status::BufError
+            && compress.total_in() as usize - base_in >= input.len()
+            && out.len() < out.capacity();
+        if done {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Compress a single block of a continuous stream, optionally primed with a preset dictionary.
+/// All but the last block are closed out with `Z_FULL_FLUSH`, which byte-aligns the output and
+/// resets the compressor's window (so the next block can start compressing independently) without
+/// ending the deflate stream; the last block is closed with `Z_FINISH`, setting `BFINAL` so the
+/// stream is valid on its own once the header and trailer are wrapped around it. Blocks smaller
+/// than `min_compress_size`, and blocks deflate fails to shrink, are written as a stored
+/// (uncompressed) block instead; see [`store_block`].
+fn compress_continuous_block(
+    buffer: &[u8],
+    dictionary: Option<&[u8]>,
+    is_last: bool,
+    level: Compression,
+    min_compress_size: usize,
+) -> Result<BlockResult, GzpError> {
+    let mut crc = Crc::new();
+    crc.update(buffer);
+    let len = buffer.len() as u64;
+
+    if buffer.len() < min_compress_size {
+        return Ok(BlockResult {
+            data: store_block(buffer, is_last),
+            crc: crc.sum(),
+            len,
+        });
+    }
+
+    let mut compress = Compress::new(level, false);
+    if let Some(dictionary) = dictionary {
+        // flate2's pure-Rust (miniz_oxide) backend doesn't expose `Compress::set_dictionary`
+        // (that method only exists behind its `zlib`/`zlib-rs` cargo features), so the dictionary
+        // is instead primed by compressing it normally with `Sync` flush (which byte-aligns
+        // output without resetting the window, unlike `Full`) and discarding the output: this
+        // leaves the compressor's LZ77 window populated with `dictionary`'s bytes exactly as
+        // `set_dictionary` would, without depending on a backend-specific API.
+        let mut discarded = Vec::with_capacity(dictionary.len());
+        run_compress(&mut compress, dictionary, &mut discarded, FlushCompress::Sync)?;
+    }
+    let flush = if is_last {
+        FlushCompress::Finish
+    } else {
+        FlushCompress::Full
+    };
+    let mut out = Vec::with_capacity(buffer.len());
+    run_compress(&mut compress, buffer, &mut out, flush)?;
+    if out.len() > buffer.len() {
+        out = store_block(buffer, is_last);
+    }
+    Ok(BlockResult {
+        data: out,
+        crc: crc.sum(),
+        len,
+    })
+}
+
+/// The last `DICT_SIZE` bytes of `data` (or all of it, if shorter), to carry over as the next
+/// block's preset dictionary.
+fn dict_tail(data: &[u8]) -> Bytes {
+    let start = data.len().saturating_sub(DICT_SIZE);
+    Bytes::copy_from_slice(&data[start..])
+}
+
+/// A message sent from [`ParGz`] to a continuous-stream compressor worker.
+struct ContinuousMessage {
+    buffer: BytesMut,
+    dictionary: Option<Bytes>,
+    is_last: bool,
+    oneshot: Sender<Result<BlockResult, GzpError>>,
+}
+
+/// The independent-member pipeline (the default): each block becomes its own complete gzip
+/// member, so blocks can be compressed, written and decoded fully independently of one another.
+struct IndependentWriter<W> {
+    sender: Option<Sender<Message>>,
+    order_sender: Option<Sender<Receiver<Result<Vec<u8>, GzpError>>>>,
+    worker_handles: Vec<JoinHandle<()>>,
+    writer_handle: Option<JoinHandle<Result<W, GzpError>>>,
+    buffer: BytesMut,
+    buffer_size: usize,
+}
+
+impl<W> IndependentWriter<W>
+where
+    W: Write + Send + 'static,
+{
+    fn flush_block(&mut self) -> Result<(), GzpError> {
+        let buffer = mem::replace(&mut self.buffer, BytesMut::with_capacity(self.buffer_size));
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let (message, block_rx) = Message::new_parts(buffer);
+        self.sender
+            .as_ref()
+            .ok_or(GzpError::ChannelSend)?
+            .send(message)
+            .map_err(|_| GzpError::ChannelSend)?;
+        self.order_sender
+            .as_ref()
+            .ok_or(GzpError::ChannelSend)?
+            .send(block_rx)
+            .map_err(|_| GzpError::ChannelSend)?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<W, GzpError> {
+        self.flush_block()?;
+        drop(self.sender.take());
+        drop(self.order_sender.take());
+        for handle in self.worker_handles.drain(..) {
+            handle.join().expect("compressor thread panicked");
+        }
+        self.writer_handle
+            .take()
+            .expect("finish called twice")
+            .join()
+            .expect("writer thread panicked")
+    }
+}
+
+/// The continuous-stream pipeline backing [`ParGzBuilder::single_member`] and
+/// [`ParGzBuilder::dictionary`]: blocks are dispatched to the worker pool as soon as they fill up,
+/// except the most recently completed one (`pending`), which is held back because we cannot yet
+/// tell whether it is the last block — only [`finish`](Self::finish) knows that for certain. Once
+/// a block is known not to be last it is sent off with `is_last: false`; the one block still held
+/// back at [`finish`](Self::finish) time (plus any leftover partial buffer) is sent with
+/// `is_last: true`.
+struct ContinuousWriter<W> {
+    sender: Option<Sender<ContinuousMessage>>,
+    order_sender: Option<Sender<Receiver<Result<BlockResult, GzpError>>>>,
+    worker_handles: Vec<JoinHandle<()>>,
+    writer_handle: Option<JoinHandle<Result<W, GzpError>>>,
+    buffer: BytesMut,
+    buffer_size: usize,
+    use_dictionary: bool,
+    pending: Option<BytesMut>,
+    dict_for_pending: Option<Bytes>,
+    last_tail: Option<Bytes>,
+}
+
+impl<W> ContinuousWriter<W>
+where
+    W: Write + Send + 'static,
+{
+    /// Send `block` off to a worker, marking it as the final block of the stream or not.
+    fn dispatch(
+        &mut self,
+        block: BytesMut,
+        dictionary: Option<Bytes>,
+        is_last: bool,
+    ) -> Result<(), GzpError> {
+        if self.use_dictionary {
+            self.last_tail = Some(dict_tail(&block));
+        }
+        let (tx, rx) = unbounded();
+        let message = ContinuousMessage {
+            buffer: block,
+            dictionary,
+            is_last,
+            oneshot: tx,
+        };
+        self.sender
+            .as_ref()
+            .ok_or(GzpError::ChannelSend)?
+            .send(message)
+            .map_err(|_| GzpError::ChannelSend)?;
+        self.order_sender
+            .as_ref()
+            .ok_or(GzpError::ChannelSend)?
+            .send(rx)
+            .map_err(|_| GzpError::ChannelSend)?;
+        Ok(())
+    }
+
+    /// Called whenever `self.buffer` fills up to a full block: the previously pending block is
+    /// now known not to be last, so send it off, and hold the new block back in its place.
+    fn flush_block(&mut self) -> Result<(), GzpError> {
+        let buffer = mem::replace(&mut self.buffer, BytesMut::with_capacity(self.buffer_size));
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        if let Some(old) = self.pending.take() {
+            let dictionary = self.dict_for_pending.take();
+            self.dispatch(old, dictionary, false)?;
+        }
+        self.pending = Some(buffer);
+        self.dict_for_pending = if self.use_dictionary {
+            self.last_tail.clone()
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<W, GzpError> {
+        let tail = mem::take(&mut self.buffer);
+        if !tail.is_empty() {
+            if let Some(old) = self.pending.take() {
+                let dictionary = self.dict_for_pending.take();
+                self.dispatch(old, dictionary, false)?;
+            }
+            let dictionary = if self.use_dictionary {
+                self.last_tail.clone()
+            } else {
+                None
+            };
+            self.dispatch(tail, dictionary, true)?;
+        } else if let Some(old) = self.pending.take() {
+            let dictionary = self.dict_for_pending.take();
+            self.dispatch(old, dictionary, true)?;
+        }
+
+        drop(self.sender.take());
+        drop(self.order_sender.take());
+        for handle in self.worker_handles.drain(..) {
+            handle.join().expect("compressor thread panicked");
+        }
+        self.writer_handle
+            .take()
+            .expect("finish called twice")
+            .join()
+            .expect("writer thread panicked")
+    }
+}
+
+enum Inner<W> {
+    Independent(IndependentWriter<W>),
+    Continuous(ContinuousWriter<W>),
+}
+
+/// A [`Write`] implementation that compresses blocks of input in parallel and writes the
+/// resulting gzip member(s), in order, to the wrapped writer.
+pub struct ParGz<W> {
+    inner: Inner<W>,
+}
+
+impl<W> ParGz<W>
+where
+    W: Write + Send + 'static,
+{
+    /// Create a [`ParGzBuilder`] for the given writer.
+    pub fn builder(writer: W) -> ParGzBuilder<W> {
+        ParGzBuilder::new(writer)
+    }
+
+    fn flush_block(&mut self) -> Result<(), GzpError> {
+        match &mut self.inner {
+            Inner::Independent(w) => w.flush_block(),
+            Inner::Continuous(w) => w.flush_block(),
+        }
+    }
+
+    /// Flush any remaining buffered bytes, shut down the worker and writer threads, and return
+    /// the wrapped writer.
+    pub fn finish(self) -> Result<W, GzpError> {
+        match self.inner {
+            Inner::Independent(w) => w.finish(),
+            Inner::Continuous(w) => w.finish(),
+        }
+    }
+}
+
+impl<W> Write for ParGz<W>
+where
+    W: Write + Send + 'static,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            Inner::Independent(w) => w.buffer.extend_from_slice(buf),
+            Inner::Continuous(w) => w.buffer.extend_from_slice(buf),
+        }
+        let over_threshold = match &self.inner {
+            Inner::Independent(w) => w.buffer.len() >= w.buffer_size,
+            Inner::Continuous(w) => w.buffer.len() >= w.buffer_size,
+        };
+        if over_threshold {
+            self.flush_block()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_block()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+/// Builder for [`ParGzReader`].
+pub struct ParGzReaderBuilder<R> {
+    inner: R,
+    num_threads: usize,
+}
+
+impl<R> ParGzReaderBuilder<R>
+where
+    R: Read + Send + 'static,
+{
+    /// Create a new builder with sane defaults.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            num_threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        }
+    }
+
+    /// Set the number of worker threads used to inflate blocks.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads.max(1);
+        self
+    }
+
+    /// Build the [`ParGzReader`], spawning the splitter and inflate worker threads.
+    pub fn build(self) -> ParGzReader {
+        let (work_tx, work_rx) = unbounded::<Message>();
+        let (order_tx, order_rx) = unbounded::<Receiver<Result<Vec<u8>, GzpError>>>();
+
+        let mut worker_handles = Vec::with_capacity(self.num_threads);
+        for _ in 0..self.num_threads {
+            let rx = work_rx.clone();
+            worker_handles.push(thread::spawn(move || {
+                for message in rx.iter() {
+                    let result = decompress_member(&message.buffer);
+                    let _ = message.oneshot.send(result);
+                }
+            }));
+        }
+
+        let splitter_handle = spawn_splitter(self.inner, work_tx, order_tx);
+
+        ParGzReader {
+            order_rx,
+            worker_handles,
+            splitter_handle: Some(splitter_handle),
+            current: Vec::new(),
+            current_pos: 0,
+            done: false,
+        }
+    }
+}
+
+/// Fully inflate a single, independent gzip member.
+fn decompress_member(member: &[u8]) -> Result<Vec<u8>, GzpError> {
+    let mut decoder = flate2::read::GzDecoder::new(member);
+    let mut out = Vec::with_capacity(member.len() * 3);
+    decoder.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// Read the whole input, split it into independent gzip members at their real boundaries (see
+/// [`find_member_starts`]), and dispatch each to the worker pool. If the input turns out to only
+/// contain a single member (e.g. it was produced by [`ParGzBuilder::single_member`]/
+/// [`ParGzBuilder::dictionary`], or by a non-block-oriented gzip implementation), fall back to
+/// decoding it sequentially instead, since there is nothing to parallelize; a plain sequential
+/// inflate handles both of those continuous formats correctly without needing to know anything
+/// about preset dictionaries, because its sliding window naturally already holds whatever bytes a
+/// dictionary would have supplied.
+fn spawn_splitter<R>(
+    mut inner: R,
+    work_tx: Sender<Message>,
+    order_tx: Sender<Receiver<Result<Vec<u8>, GzpError>>>,
+) -> JoinHandle<Result<(), GzpError>>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        // Deflate streams can't be split without being decoded, so the whole input has to be
+        // buffered before member boundaries can be found. `chunk`-sized reads (rather than one
+        // `read_to_end`) keep this resilient to readers that only ever hand back small amounts of
+        // data per call.
+        let mut raw = BytesMut::new();
+        let mut chunk = vec![0u8; BUFSIZE];
+        loop {
+            let n = inner.read(&mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            raw.extend_from_slice(&chunk[..n]);
+        }
+
+        let member_starts = find_member_starts(&raw);
+        if member_starts.len() <= 1 {
+            // Only one member (or none): no boundaries to split on, decode sequentially.
+            let mut decoder = MultiGzDecoder::new(&raw[..]);
+            let mut out = Vec::new();
+            decoder.read_to_end(&mut out)?;
+            let (message, block_rx) = Message::new_parts(BytesMut::new());
+            let _ = message.oneshot.send(Ok(out));
+            order_tx
+                .send(block_rx)
+                .map_err(|_| GzpError::ChannelSend)?;
+            return Ok(());
+        }
+
+        let mut bounds = member_starts;
+        bounds.push(raw.len());
+        for window in bounds.windows(2) {
+            let (start, end) = (window[0], window[1]);
+            let member = BytesMut::from(&raw[start..end]);
+            let (message, block_rx) = Message::new_parts(member);
+            work_tx.send(message).map_err(|_| GzpError::ChannelSend)?;
+            order_tx
+                .send(block_rx)
+                .map_err(|_| GzpError::ChannelSend)?;
+        }
+        Ok(())
+    })
+}
+
+/// Find the start offset of every gzip member in `raw`. Always includes offset `0`.
+///
+/// The gzip magic bytes aren't reserved: they can and do occur by chance inside ordinary
+/// compressed or stored-block payload data, so scanning for them anywhere in `raw` risks false
+/// splits. Instead, walk forward from each known-good start and use [`member_len_at`] to decode
+/// that member for real, which can only agree that a member ends where it really does, since a
+/// truncated or corrupt deflate stream simply fails to decode.
+fn find_member_starts(raw: &[u8]) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut pos = 0;
+    while pos < raw.len() {
+        match member_len_at(&raw[pos..]) {
+            Some(len) if len > 0 => {
+                pos += len;
+                if pos < raw.len() {
+                    starts.push(pos);
+                }
+            }
+            // Not a complete, valid member (e.g. a truncated final one): stop here and let the
+            // remaining bytes be handled, and fail, as part of the last member.
+            _ => break,
+        }
+    }
+    starts
+}
+
+/// Decode the gzip member starting at the beginning of `raw` and return how many bytes of `raw`
+/// it occupies (i.e. the offset of whatever follows it), or `None` if `raw` doesn't start with a
+/// complete, valid member.
+///
+/// This must use `flate2::bufread::GzDecoder` directly over the `Cursor`, not
+/// `flate2::read::GzDecoder`: the latter wraps its source in its own `BufReader`, which fills
+/// that buffer by reading ahead from `cursor` well past the end of this member into whatever
+/// follows, advancing `cursor.position()` far beyond the member's true boundary. The `bufread`
+/// decoder instead reads directly from the `Cursor`'s `BufRead` impl and only `consume()`s the
+/// bytes it actually used, so `cursor.position()` tracks the member's real length.
+fn member_len_at(raw: &[u8]) -> Option<usize> {
+    let mut cursor = io::Cursor::new(raw);
+    {
+        let mut decoder = flate2::bufread::GzDecoder::new(&mut cursor);
+        io::copy(&mut decoder, &mut io::sink()).ok()?;
+    }
+    let consumed = cursor.position() as usize;
+    if consumed == 0 {
+        None
+    } else {
+        Some(consumed)
+    }
+}
+
+/// A [`Read`] implementation that inflates a gzip stream's members in parallel and yields their
+/// decompressed bytes back in the original order.
+pub struct ParGzReader {
+    order_rx: Receiver<Receiver<Result<Vec<u8>, GzpError>>>,
+    worker_handles: Vec<JoinHandle<()>>,
+    splitter_handle: Option<JoinHandle<Result<(), GzpError>>>,
+    current: Vec<u8>,
+    current_pos: usize,
+    done: bool,
+}
+
+impl ParGzReader {
+    /// Create a [`ParGzReaderBuilder`] for the given reader.
+    pub fn builder<R>(inner: R) -> ParGzReaderBuilder<R>
+    where
+        R: Read + Send + 'static,
+    {
+        ParGzReaderBuilder::new(inner)
+    }
+}
+
+impl Read for ParGzReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.current_pos < self.current.len() {
+                let n = (self.current.len() - self.current_pos).min(buf.len());
+                buf[..n].copy_from_slice(&self.current[self.current_pos..self.current_pos + n]);
+                self.current_pos += n;
+                return Ok(n);
+            }
+            if self.done {
+                return Ok(0);
+            }
+            match self.order_rx.recv() {
+                Ok(block_rx) => {
+                    let block = block_rx
+                        .recv()
+                        .map_err(GzpError::from)
+                        .and_then(|r| r)
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    self.current = block;
+                    self.current_pos = 0;
+                }
+                Err(_) => {
+                    self.done = true;
+                    for handle in self.worker_handles.drain(..) {
+                        let _ = handle.join();
+                    }
+                    if let Some(handle) = self.splitter_handle.take() {
+                        handle
+                            .join()
+                            .expect("splitter thread panicked")
+                            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+
+    use super::{find_member_starts, ParGz, ParGzReader};
+
+    // Picked to land at various offsets relative to a block boundary: the bug this guards against
+    // (`compress_continuous_block` stopping before the compressor had actually finished emitting a
+    // flush/finish) depended on both the payload's entropy and exactly where it fell in a block.
+    const PAYLOADS: &[&[u8]] = &[b"hello world\n", &[7u8; 1], &[7u8; 100], &[7u8; 256]];
+
+    fn assert_round_trips(par_gz: ParGz<Vec<u8>>, payload: &[u8]) {
+        let compressed = par_gz.finish().unwrap();
+        let mut reader = ParGzReader::builder(Cursor::new(compressed)).build();
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, payload);
+    }
+
+    #[test]
+    fn single_member_round_trips() {
+        for payload in PAYLOADS {
+            let mut par_gz = ParGz::builder(vec![]).single_member(true).build();
+            par_gz.write_all(payload).unwrap();
+            assert_round_trips(par_gz, payload);
+        }
+    }
+
+    #[test]
+    fn dictionary_round_trips_across_multiple_blocks() {
+        for payload in PAYLOADS {
+            let mut par_gz = ParGz::builder(vec![])
+                .dictionary(true)
+                .buffer_size(4)
+                .build();
+            // Written one byte at a time so the buffer_size threshold is actually crossed more
+            // than once: a single write_all of the whole payload would only ever trigger one flush.
+            for byte in payload.iter() {
+                par_gz.write_all(&[*byte]).unwrap();
+            }
+            assert_round_trips(par_gz, payload);
+        }
+    }
+
+    #[test]
+    fn independent_members_round_trip_via_parallel_split() {
+        // Large and spread across enough small members that the combined output exceeds a
+        // BufReader's default lookahead, so a boundary-finder that reads ahead past a member's
+        // true end (rather than stopping exactly at it) would be caught out here.
+        let payload: Vec<u8> = (0..20_000).map(|i| (i % 251) as u8).collect();
+        let mut par_gz = ParGz::builder(vec![]).buffer_size(64).build();
+        for chunk in payload.chunks(64) {
+            par_gz.write_all(chunk).unwrap();
+        }
+        let compressed = par_gz.finish().unwrap();
+
+        assert!(find_member_starts(&compressed).len() > 1);
+
+        let mut reader = ParGzReader::builder(Cursor::new(compressed)).build();
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, payload);
+    }
+}
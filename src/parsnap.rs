@@ -0,0 +1,436 @@
+//! Parallel snappy (framed) compression and decompression.
+//!
+//! [`ParSnap`] and [`ParSnapReader`] are the snappy-framed-format counterparts of
+//! [`crate::pargz::ParGz`] and [`crate::pargz::ParGzReader`]: each block is compressed into its
+//! own independent snappy stream (starting with the frame format's stream identifier chunk), and
+//! [`ParSnapReader`] splits a stream back into those independent chunks to inflate them in
+//! parallel.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::{Cursor, Read, Write};
+//!
+//! use gzp::parsnap::{ParSnap, ParSnapReader};
+//!
+//! let mut par_snap = ParSnap::builder(vec![]).build();
+//! par_snap.write_all(b"This is a first test line\n").unwrap();
+//! par_snap.write_all(b"This is a second test line\n").unwrap();
+//! let compressed = par_snap.finish().unwrap();
+//!
+//! let mut reader = ParSnapReader::builder(Cursor::new(compressed)).build();
+//! let mut decompressed = String::new();
+//! reader.read_to_string(&mut decompressed).unwrap();
+//! assert_eq!(decompressed, "This is a first test line\nThis is a second test line\n");
+//! ```
+use std::io::{self, Read, Write};
+use std::mem;
+use std::thread::{self, JoinHandle};
+
+use bytes::BytesMut;
+use flume::{unbounded, Receiver, Sender};
+use snap::read::FrameDecoder;
+use snap::write::FrameEncoder;
+
+use crate::{GzpError, Message, BUFSIZE};
+
+/// Builder for [`ParSnap`].
+pub struct ParSnapBuilder<W> {
+    writer: W,
+    buffer_size: usize,
+    num_threads: usize,
+}
+
+impl<W> ParSnapBuilder<W>
+where
+    W: Write + Send + 'static,
+{
+    /// Create a new builder with sane defaults.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buffer_size: BUFSIZE,
+            num_threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        }
+    }
+
+    /// Set the size of the blocks that are handed off to the compressor threads.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Set the number of worker threads used to compress blocks.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads.max(1);
+        self
+    }
+
+    /// Build the [`ParSnap`] writer, spawning the compressor and writer threads.
+    pub fn build(self) -> ParSnap<W> {
+        let (tx, rx) = unbounded::<Message>();
+        let (order_tx, order_rx) = unbounded::<Receiver<Result<Vec<u8>, GzpError>>>();
+
+        let mut worker_handles = Vec::with_capacity(self.num_threads);
+        for _ in 0..self.num_threads {
+            let rx = rx.clone();
+            worker_handles.push(thread::spawn(move || {
+                for message in rx.iter() {
+                    let result = compress_block(&message.buffer);
+                    let _ = message.oneshot.send(result);
+                }
+            }));
+        }
+
+        let mut writer = self.writer;
+        let writer_handle = thread::spawn(move || -> Result<W, GzpError> {
+            for block_rx in order_rx.iter() {
+                let block = block_rx.recv()??;
+                writer.write_all(&block)?;
+            }
+            writer.flush()?;
+            Ok(writer)
+        });
+
+        ParSnap {
+            sender: Some(tx),
+            order_sender: Some(order_tx),
+            worker_handles,
+            writer_handle: Some(writer_handle),
+            buffer: BytesMut::with_capacity(self.buffer_size),
+            buffer_size: self.buffer_size,
+        }
+    }
+}
+
+/// Compress a single block into its own independent snappy frame-format stream.
+fn compress_block(buffer: &[u8]) -> Result<Vec<u8>, GzpError> {
+    let mut encoder = FrameEncoder::new(Vec::with_capacity(buffer.len()));
+    encoder.write_all(buffer)?;
+    encoder.flush()?;
+    encoder.into_inner().map_err(|_| GzpError::Unknown)
+}
+
+/// A [`Write`] implementation that compresses blocks of input in parallel and writes the
+/// resulting snappy streams, in order, to the wrapped writer.
+pub struct ParSnap<W> {
+    sender: Option<Sender<Message>>,
+    order_sender: Option<Sender<Receiver<Result<Vec<u8>, GzpError>>>>,
+    worker_handles: Vec<JoinHandle<()>>,
+    writer_handle: Option<JoinHandle<Result<W, GzpError>>>,
+    buffer: BytesMut,
+    buffer_size: usize,
+}
+
+impl<W> ParSnap<W>
+where
+    W: Write + Send + 'static,
+{
+    /// Create a [`ParSnapBuilder`] for the given writer.
+    pub fn builder(writer: W) -> ParSnapBuilder<W> {
+        ParSnapBuilder::new(writer)
+    }
+
+    fn send_block(&mut self, buffer: BytesMut) -> Result<(), GzpError> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let (message, block_rx) = Message::new_parts(buffer);
+        self.sender
+            .as_ref()
+            .ok_or(GzpError::ChannelSend)?
+            .send(message)
+            .map_err(|_| GzpError::ChannelSend)?;
+        self.order_sender
+            .as_ref()
+            .ok_or(GzpError::ChannelSend)?
+            .send(block_rx)
+            .map_err(|_| GzpError::ChannelSend)?;
+        Ok(())
+    }
+
+    fn flush_block(&mut self) -> Result<(), GzpError> {
+        let buffer = mem::replace(&mut self.buffer, BytesMut::with_capacity(self.buffer_size));
+        self.send_block(buffer)
+    }
+
+    /// Flush any remaining buffered bytes, shut down the worker and writer threads, and return
+    /// the wrapped writer.
+    pub fn finish(mut self) -> Result<W, GzpError> {
+        self.flush_block()?;
+        drop(self.sender.take());
+        drop(self.order_sender.take());
+        for handle in self.worker_handles.drain(..) {
+            handle.join().expect("compressor thread panicked");
+        }
+        self.writer_handle
+            .take()
+            .expect("finish called twice")
+            .join()
+            .expect("writer thread panicked")
+    }
+}
+
+impl<W> Write for ParSnap<W>
+where
+    W: Write + Send + 'static,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= self.buffer_size {
+            self.flush_block()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_block()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+/// Builder for [`ParSnapReader`].
+pub struct ParSnapReaderBuilder<R> {
+    inner: R,
+    num_threads: usize,
+}
+
+impl<R> ParSnapReaderBuilder<R>
+where
+    R: Read + Send + 'static,
+{
+    /// Create a new builder with sane defaults.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            num_threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        }
+    }
+
+    /// Set the number of worker threads used to decompress blocks.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads.max(1);
+        self
+    }
+
+    /// Build the [`ParSnapReader`], spawning the splitter and decompress worker threads.
+    pub fn build(self) -> ParSnapReader {
+        let (work_tx, work_rx) = unbounded::<Message>();
+        let (order_tx, order_rx) = unbounded::<Receiver<Result<Vec<u8>, GzpError>>>();
+
+        let mut worker_handles = Vec::with_capacity(self.num_threads);
+        for _ in 0..self.num_threads {
+            let rx = work_rx.clone();
+            worker_handles.push(thread::spawn(move || {
+                for message in rx.iter() {
+                    let result = decompress_stream(&message.buffer);
+                    let _ = message.oneshot.send(result);
+                }
+            }));
+        }
+
+        let splitter_handle = spawn_splitter(self.inner, work_tx, order_tx);
+
+        ParSnapReader {
+            order_rx,
+            worker_handles,
+            splitter_handle: Some(splitter_handle),
+            current: Vec::new(),
+            current_pos: 0,
+            done: false,
+        }
+    }
+}
+
+/// Fully decompress a single, independent snappy frame-format stream.
+fn decompress_stream(stream: &[u8]) -> Result<Vec<u8>, GzpError> {
+    let mut decoder = FrameDecoder::new(stream);
+    let mut out = Vec::with_capacity(stream.len() * 3);
+    decoder.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// Read the whole input, split it into independent snappy streams at their stream-identifier
+/// chunk boundaries, and dispatch each to the worker pool. Falls back to decoding sequentially
+/// when there is only a single stream to begin with.
+fn spawn_splitter<R>(
+    mut inner: R,
+    work_tx: Sender<Message>,
+    order_tx: Sender<Receiver<Result<Vec<u8>, GzpError>>>,
+) -> JoinHandle<Result<(), GzpError>>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut raw = BytesMut::new();
+        let mut chunk = vec![0u8; BUFSIZE];
+        loop {
+            let n = inner.read(&mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            raw.extend_from_slice(&chunk[..n]);
+        }
+
+        let stream_starts = find_stream_starts(&raw);
+        if stream_starts.len() <= 1 {
+            let mut decoder = FrameDecoder::new(&raw[..]);
+            let mut out = Vec::new();
+            decoder.read_to_end(&mut out)?;
+            let (message, block_rx) = Message::new_parts(BytesMut::new());
+            let _ = message.oneshot.send(Ok(out));
+            order_tx
+                .send(block_rx)
+                .map_err(|_| GzpError::ChannelSend)?;
+            return Ok(());
+        }
+
+        let mut bounds = stream_starts;
+        bounds.push(raw.len());
+        for window in bounds.windows(2) {
+            let (start, end) = (window[0], window[1]);
+            let stream = BytesMut::from(&raw[start..end]);
+            let (message, block_rx) = Message::new_parts(stream);
+            work_tx.send(message).map_err(|_| GzpError::ChannelSend)?;
+            order_tx
+                .send(block_rx)
+                .map_err(|_| GzpError::ChannelSend)?;
+        }
+        Ok(())
+    })
+}
+
+/// Find the start offset of every snappy stream in `raw`. Always includes offset `0`.
+///
+/// Rather than scanning for the stream identifier bytes anywhere in `raw` (which risks a false
+/// split, since those bytes aren't reserved and can occur by chance inside an uncompressed chunk's
+/// payload), walk the chunk structure forward from the known-good start at offset `0`: every chunk
+/// is a 1-byte type plus a 3-byte little-endian length, which is enough to skip straight to the
+/// next chunk without looking at its payload at all. Because a chunk type is only ever read at a
+/// position reached this way, a stream identifier chunk can only be found at a real boundary.
+fn find_stream_starts(raw: &[u8]) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut pos = 0;
+    while pos + 4 <= raw.len() {
+        let chunk_type = raw[pos];
+        let chunk_len = u32::from_le_bytes([raw[pos + 1], raw[pos + 2], raw[pos + 3], 0]) as usize;
+        if pos + 4 + chunk_len > raw.len() {
+            // Truncated chunk: stop here and let the remainder be handled, and fail, as part of
+            // the current stream.
+            break;
+        }
+        if chunk_type == 0xff && pos != 0 {
+            starts.push(pos);
+        }
+        pos += 4 + chunk_len;
+    }
+    starts
+}
+
+/// A [`Read`] implementation that decompresses a concatenation of snappy streams in parallel and
+/// yields their decompressed bytes back in the original order.
+pub struct ParSnapReader {
+    order_rx: Receiver<Receiver<Result<Vec<u8>, GzpError>>>,
+    worker_handles: Vec<JoinHandle<()>>,
+    splitter_handle: Option<JoinHandle<Result<(), GzpError>>>,
+    current: Vec<u8>,
+    current_pos: usize,
+    done: bool,
+}
+
+impl ParSnapReader {
+    /// Create a [`ParSnapReaderBuilder`] for the given reader.
+    pub fn builder<R>(inner: R) -> ParSnapReaderBuilder<R>
+    where
+        R: Read + Send + 'static,
+    {
+        ParSnapReaderBuilder::new(inner)
+    }
+}
+
+impl Read for ParSnapReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.current_pos < self.current.len() {
+                let n = (self.current.len() - self.current_pos).min(buf.len());
+                buf[..n].copy_from_slice(&self.current[self.current_pos..self.current_pos + n]);
+                self.current_pos += n;
+                return Ok(n);
+            }
+            if self.done {
+                return Ok(0);
+            }
+            match self.order_rx.recv() {
+                Ok(block_rx) => {
+                    let block = block_rx
+                        .recv()
+                        .map_err(GzpError::from)
+                        .and_then(|r| r)
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    self.current = block;
+                    self.current_pos = 0;
+                }
+                Err(_) => {
+                    self.done = true;
+                    for handle in self.worker_handles.drain(..) {
+                        let _ = handle.join();
+                    }
+                    if let Some(handle) = self.splitter_handle.take() {
+                        handle
+                            .join()
+                            .expect("splitter thread panicked")
+                            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+
+    use super::{find_stream_starts, ParSnap, ParSnapReader};
+
+    const PAYLOADS: &[&[u8]] = &[b"hello world\n", &[7u8; 1], &[7u8; 100], &[7u8; 256]];
+
+    fn assert_round_trips(par_snap: ParSnap<Vec<u8>>, payload: &[u8]) {
+        let compressed = par_snap.finish().unwrap();
+        let mut reader = ParSnapReader::builder(Cursor::new(compressed)).build();
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, payload);
+    }
+
+    #[test]
+    fn single_stream_round_trips() {
+        for payload in PAYLOADS {
+            let mut par_snap = ParSnap::builder(vec![]).build();
+            par_snap.write_all(payload).unwrap();
+            assert_round_trips(par_snap, payload);
+        }
+    }
+
+    #[test]
+    fn multiple_streams_round_trip_via_parallel_split() {
+        let payload = [7u8; 256];
+        let mut par_snap = ParSnap::builder(vec![]).buffer_size(4).build();
+        // Written one byte at a time so the buffer_size threshold is actually crossed more than
+        // once: a single write_all of the whole payload would only ever trigger one flush.
+        for byte in &payload {
+            par_snap.write_all(&[*byte]).unwrap();
+        }
+        let compressed = par_snap.finish().unwrap();
+
+        // Make sure this is actually exercising the parallel-split path in ParSnapReader, not
+        // just the single-stream fallback.
+        assert!(find_stream_starts(&compressed).len() > 1);
+
+        let mut reader = ParSnapReader::builder(Cursor::new(compressed)).build();
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, payload);
+    }
+}
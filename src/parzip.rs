@@ -0,0 +1,473 @@
+//! Parallel ZIP archive writing.
+//!
+//! [`ParZip`] builds a standard ZIP archive out of entries added one at a time via
+//! [`ParZip::add_file`], compressing each across the same kind of worker threadpool the other
+//! `par*` writers use. Entries bigger than [`SPLIT_THRESHOLD`] are split into `~1` MiB chunks so
+//! a single large entry can be compressed by several workers at once rather than one; the chunks
+//! are deflated independently (closed with `Z_FULL_FLUSH`, except the last which uses
+//! `Z_FINISH`, exactly like [`crate::pargz::ParGzBuilder::single_member`]) and recombined, in
+//! order, into one deflate stream per entry, folding the per-chunk CRC32s into the entry's CRC32
+//! with [`crate::crc::crc32_combine`] as they arrive.
+//!
+//! Only ZIP32 is supported: entries, and the archive as a whole, are assumed to fit comfortably
+//! under 4 GiB.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::Cursor;
+//!
+//! use gzp::parzip::ParZip;
+//!
+//! let mut par_zip = ParZip::builder(vec![]).build();
+//! par_zip.add_file("a.txt", Cursor::new(b"This is the first file\n")).unwrap();
+//! par_zip.add_file("b.txt", Cursor::new(b"This is the second file\n")).unwrap();
+//! let archive = par_zip.finish().unwrap();
+//! assert_eq!(&archive[..2], b"PK");
+//! ```
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::thread::{self, JoinHandle};
+
+use bytes::BytesMut;
+use flate2::{Compress, Compression, Crc, FlushCompress, Status};
+use flume::{unbounded, Receiver, Sender};
+
+use crate::crc::crc32_combine;
+use crate::GzpError;
+
+/// Entries larger than this are split into [`CHUNK_SIZE`] chunks so their compression can be
+/// parallelized across the worker pool; smaller entries are compressed as a single chunk.
+const SPLIT_THRESHOLD: usize = 5 * 1024 * 1024;
+
+/// How many entries [`ParZip::add_file`] will let sit dispatched-but-not-yet-written before it
+/// blocks on the oldest one to keep memory bounded. Keeping several entries' chunks in flight
+/// across the worker pool at once, rather than waiting on each entry before moving to the next, is
+/// what lets many small entries compress in parallel instead of one at a time.
+const MAX_INFLIGHT_ENTRIES: usize = 8;
+
+/// The target chunk size used to split large entries.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+const LOCAL_FILE_HEADER_SIG: u32 = 0x0403_4b50;
+const CENTRAL_DIR_HEADER_SIG: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIR_SIG: u32 = 0x0605_4b50;
+
+/// ZIP compression method `8`: deflate.
+const DEFLATE: u16 = 8;
+/// Version 2.0, the lowest version that supports deflate.
+const VERSION_NEEDED: u16 = 20;
+/// Size of a local file header, excluding the variable-length file name.
+const LOCAL_HEADER_FIXED_LEN: u64 = 30;
+/// Size of a central directory file header, excluding the variable-length file name.
+const CENTRAL_DIR_HEADER_FIXED_LEN: u64 = 46;
+
+/// Builder for [`ParZip`].
+pub struct ParZipBuilder<W> {
+    writer: W,
+    num_threads: usize,
+    compression_level: Compression,
+}
+
+impl<W> ParZipBuilder<W>
+where
+    W: Write + Send + 'static,
+{
+    /// Create a new builder with sane defaults.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            num_threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            compression_level: Compression::default(),
+        }
+    }
+
+    /// Set the number of worker threads used to compress chunks.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads.max(1);
+        self
+    }
+
+    /// Set the deflate compression level used for every entry.
+    pub fn compression_level(mut self, compression_level: Compression) -> Self {
+        self.compression_level = compression_level;
+        self
+    }
+
+    /// Build the [`ParZip`] writer, spawning the compressor worker threads.
+    pub fn build(self) -> ParZip<W> {
+        let (tx, rx) = unbounded::<ZipMessage>();
+
+        let mut worker_handles = Vec::with_capacity(self.num_threads);
+        for _ in 0..self.num_threads {
+            let rx = rx.clone();
+            let level = self.compression_level;
+            worker_handles.push(thread::spawn(move || {
+                for message in rx.iter() {
+                    let result = compress_chunk(&message.buffer, message.is_last, level);
+                    let _ = message.oneshot.send(result);
+                }
+            }));
+        }
+
+        ParZip {
+            sender: Some(tx),
+            worker_handles,
+            writer: self.writer,
+            offset: 0,
+            entries: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+/// The result of compressing one chunk of an entry: the raw deflate bytes plus the CRC32 and
+/// length of the uncompressed input, folded into the entry's running CRC32 and size via
+/// [`crc32_combine`].
+struct ZipChunkResult {
+    data: Vec<u8>,
+    crc: u32,
+    len: u64,
+}
+
+/// A message sent from [`ParZip`] to a compressor worker.
+struct ZipMessage {
+    buffer: BytesMut,
+    is_last: bool,
+    oneshot: Sender<Result<ZipChunkResult, GzpError>>,
+}
+
+/// Drive `compress` over all of `input` with the given `flush`, appending output to `out`, looping
+/// until the flush has definitely been fully applied. A single `compress_vec` call isn't always
+/// enough: it can return having consumed all of `input` but before the compressor has actually
+/// finished emitting that flush's output, requiring another call (with an empty remaining-input
+/// slice) to find out. For `FlushCompress::Finish` that means looping until `Status::StreamEnd`;
+/// other flush kinds never report `StreamEnd`, so completion instead means all input was consumed
+/// and the call left spare room in `out`, i.e. it stopped because there was nothing left to flush,
+/// not because `out` ran out of space. `Status::BufError` specifically means the latter: `out` was
+/// full, not that the flush is done, so it must never be treated as a terminal state; we grow `out`
+/// and go around again instead.
+fn run_compress(
+    compress: &mut Compress,
+    input: &[u8],
+    out: &mut Vec<u8>,
+    flush: FlushCompress,
+) -> Result<(), GzpError> {
+    let base_in = compress.total_in() as usize;
+    loop {
+        let consumed = compress.total_in() as usize - base_in;
+        if out.len() == out.capacity() {
+            out.reserve(input.len().max(1024));
+        }
+        let status = compress
+            .compress_vec(&input[consumed..], out, flush)
+            .map_err(|_| GzpError::Unknown)?;
+        if status == Status::StreamEnd {
+            break;
+        }
+        let done = status != Status::BufError
+            && compress.total_in() as usize - base_in >= input.len()
+            && out.len() < out.capacity();
+        if done {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Compress one chunk of an entry. All but the last chunk are closed out with `Z_FULL_FLUSH`,
+/// which byte-aligns the output without ending the deflate stream, so the chunks concatenate
+/// into one valid deflate stream for the whole entry; the last chunk uses `Z_FINISH`.
+fn compress_chunk(buffer: &[u8], is_last: bool, level: Compression) -> Result<ZipChunkResult, GzpError> {
+    let mut crc = Crc::new();
+    crc.update(buffer);
+
+    let mut compress = Compress::new(level, false);
+    let flush = if is_last {
+        FlushCompress::Finish
+    } else {
+        FlushCompress::Full
+    };
+    let mut out = Vec::with_capacity(buffer.len());
+    run_compress(&mut compress, buffer, &mut out, flush)?;
+    Ok(ZipChunkResult {
+        data: out,
+        crc: crc.sum(),
+        len: buffer.len() as u64,
+    })
+}
+
+/// A finished entry's central directory bookkeeping.
+struct CentralDirEntry {
+    name: Vec<u8>,
+    crc: u32,
+    compressed_size: u32,
+    uncompressed_size: u32,
+    local_header_offset: u32,
+}
+
+/// An entry whose chunks have been dispatched to the worker pool but not yet collected and
+/// written out.
+struct PendingEntry {
+    name: Vec<u8>,
+    receivers: Vec<Receiver<Result<ZipChunkResult, GzpError>>>,
+}
+
+/// Builds a ZIP archive by compressing entries across a pool of worker threads.
+pub struct ParZip<W> {
+    sender: Option<Sender<ZipMessage>>,
+    worker_handles: Vec<JoinHandle<()>>,
+    writer: W,
+    offset: u64,
+    entries: Vec<CentralDirEntry>,
+    pending: VecDeque<PendingEntry>,
+}
+
+impl<W> ParZip<W>
+where
+    W: Write + Send + 'static,
+{
+    /// Create a [`ParZipBuilder`] for the given writer.
+    pub fn builder(writer: W) -> ParZipBuilder<W> {
+        ParZipBuilder::new(writer)
+    }
+
+    /// Read `reader` to completion and add it to the archive as an entry named `name`, splitting
+    /// it into chunks compressed in parallel if it's larger than [`SPLIT_THRESHOLD`].
+    ///
+    /// The entry's chunks are dispatched to the worker pool without blocking on their results, so
+    /// that many small entries can be compressing concurrently across the pool rather than one at
+    /// a time; this only blocks if there are already [`MAX_INFLIGHT_ENTRIES`] entries awaiting
+    /// collection, in which case the oldest of those is collected and written first.
+    pub fn add_file<R: Read>(&mut self, name: &str, mut reader: R) -> Result<(), GzpError> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+
+        let chunks: Vec<&[u8]> = if buf.len() > SPLIT_THRESHOLD {
+            buf.chunks(CHUNK_SIZE).collect()
+        } else {
+            vec![&buf[..]]
+        };
+        let num_chunks = chunks.len();
+
+        let mut receivers = Vec::with_capacity(num_chunks);
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let (tx, rx) = unbounded();
+            let message = ZipMessage {
+                buffer: BytesMut::from(chunk),
+                is_last: i + 1 == num_chunks,
+                oneshot: tx,
+            };
+            self.sender
+                .as_ref()
+                .ok_or(GzpError::ChannelSend)?
+                .send(message)
+                .map_err(|_| GzpError::ChannelSend)?;
+            receivers.push(rx);
+        }
+
+        self.pending.push_back(PendingEntry {
+            name: name.as_bytes().to_vec(),
+            receivers,
+        });
+        if self.pending.len() > MAX_INFLIGHT_ENTRIES {
+            self.write_oldest_pending()?;
+        }
+        Ok(())
+    }
+
+    /// Collect the oldest dispatched-but-not-yet-written entry's chunks, blocking on them if
+    /// they're not all done yet, and write it out.
+    fn write_oldest_pending(&mut self) -> Result<(), GzpError> {
+        let entry = self
+            .pending
+            .pop_front()
+            .expect("write_oldest_pending called with no pending entries");
+
+        let mut data = Vec::new();
+        let mut crc = 0u32;
+        let mut len = 0u64;
+        for rx in entry.receivers {
+            let chunk = rx.recv()??;
+            data.extend_from_slice(&chunk.data);
+            crc = crc32_combine(crc, chunk.crc, chunk.len);
+            len += chunk.len;
+        }
+
+        let local_header_offset = self.offset;
+        write_local_header(&mut self.writer, &entry.name, crc, data.len() as u32, len as u32)?;
+        self.writer.write_all(&data)?;
+        self.offset += LOCAL_HEADER_FIXED_LEN + entry.name.len() as u64 + data.len() as u64;
+
+        self.entries.push(CentralDirEntry {
+            name: entry.name,
+            crc,
+            compressed_size: data.len() as u32,
+            uncompressed_size: len as u32,
+            local_header_offset: local_header_offset as u32,
+        });
+        Ok(())
+    }
+
+    /// Shut down the worker threads, write the central directory, and return the wrapped writer.
+    pub fn finish(mut self) -> Result<W, GzpError> {
+        while !self.pending.is_empty() {
+            self.write_oldest_pending()?;
+        }
+        drop(self.sender.take());
+        for handle in self.worker_handles.drain(..) {
+            handle.join().expect("compressor thread panicked");
+        }
+
+        let central_dir_offset = self.offset;
+        let mut central_dir_size = 0u64;
+        for entry in &self.entries {
+            write_central_dir_header(&mut self.writer, entry)?;
+            central_dir_size += CENTRAL_DIR_HEADER_FIXED_LEN + entry.name.len() as u64;
+        }
+        write_end_of_central_dir(
+            &mut self.writer,
+            self.entries.len() as u16,
+            central_dir_size as u32,
+            central_dir_offset as u32,
+        )?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Write a ZIP local file header (the fixed fields plus the file name) immediately before an
+/// entry's compressed data.
+fn write_local_header<W: Write>(
+    writer: &mut W,
+    name: &[u8],
+    crc: u32,
+    compressed_size: u32,
+    uncompressed_size: u32,
+) -> io::Result<()> {
+    writer.write_all(&LOCAL_FILE_HEADER_SIG.to_le_bytes())?;
+    writer.write_all(&VERSION_NEEDED.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?; // general purpose bit flag
+    writer.write_all(&DEFLATE.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?; // last mod file time
+    writer.write_all(&0u16.to_le_bytes())?; // last mod file date
+    writer.write_all(&crc.to_le_bytes())?;
+    writer.write_all(&compressed_size.to_le_bytes())?;
+    writer.write_all(&uncompressed_size.to_le_bytes())?;
+    writer.write_all(&(name.len() as u16).to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?; // extra field length
+    writer.write_all(name)?;
+    Ok(())
+}
+
+/// Write one entry's central directory file header.
+fn write_central_dir_header<W: Write>(writer: &mut W, entry: &CentralDirEntry) -> io::Result<()> {
+    writer.write_all(&CENTRAL_DIR_HEADER_SIG.to_le_bytes())?;
+    writer.write_all(&VERSION_NEEDED.to_le_bytes())?; // version made by
+    writer.write_all(&VERSION_NEEDED.to_le_bytes())?; // version needed to extract
+    writer.write_all(&0u16.to_le_bytes())?; // general purpose bit flag
+    writer.write_all(&DEFLATE.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?; // last mod file time
+    writer.write_all(&0u16.to_le_bytes())?; // last mod file date
+    writer.write_all(&entry.crc.to_le_bytes())?;
+    writer.write_all(&entry.compressed_size.to_le_bytes())?;
+    writer.write_all(&entry.uncompressed_size.to_le_bytes())?;
+    writer.write_all(&(entry.name.len() as u16).to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?; // extra field length
+    writer.write_all(&0u16.to_le_bytes())?; // file comment length
+    writer.write_all(&0u16.to_le_bytes())?; // disk number start
+    writer.write_all(&0u16.to_le_bytes())?; // internal file attributes
+    writer.write_all(&0u32.to_le_bytes())?; // external file attributes
+    writer.write_all(&entry.local_header_offset.to_le_bytes())?;
+    writer.write_all(&entry.name)?;
+    Ok(())
+}
+
+/// Write the end of central directory record that closes out the archive.
+fn write_end_of_central_dir<W: Write>(
+    writer: &mut W,
+    num_entries: u16,
+    central_dir_size: u32,
+    central_dir_offset: u32,
+) -> io::Result<()> {
+    writer.write_all(&END_OF_CENTRAL_DIR_SIG.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?; // number of this disk
+    writer.write_all(&0u16.to_le_bytes())?; // disk where central directory starts
+    writer.write_all(&num_entries.to_le_bytes())?; // entries on this disk
+    writer.write_all(&num_entries.to_le_bytes())?; // total entries
+    writer.write_all(&central_dir_size.to_le_bytes())?;
+    writer.write_all(&central_dir_offset.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?; // comment length
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use flate2::read::DeflateDecoder;
+
+    use super::{ParZip, SPLIT_THRESHOLD};
+
+    struct Entry {
+        name: String,
+        data: Vec<u8>,
+    }
+
+    /// Parse just enough of a ZIP archive's local file headers to check what [`ParZip`] wrote,
+    /// without pulling in a ZIP-reading dependency.
+    fn read_entries(archive: &[u8]) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while pos + 4 <= archive.len() && archive[pos..pos + 4] == [0x50, 0x4b, 0x03, 0x04] {
+            let compressed_size =
+                u32::from_le_bytes(archive[pos + 18..pos + 22].try_into().unwrap()) as usize;
+            let uncompressed_size =
+                u32::from_le_bytes(archive[pos + 22..pos + 26].try_into().unwrap()) as usize;
+            let name_len = u16::from_le_bytes(archive[pos + 26..pos + 28].try_into().unwrap()) as usize;
+            let extra_len = u16::from_le_bytes(archive[pos + 28..pos + 30].try_into().unwrap()) as usize;
+            let name_start = pos + 30;
+            let data_start = name_start + name_len + extra_len;
+            let name = String::from_utf8(archive[name_start..name_start + name_len].to_vec()).unwrap();
+
+            let mut data = Vec::with_capacity(uncompressed_size);
+            DeflateDecoder::new(&archive[data_start..data_start + compressed_size])
+                .read_to_end(&mut data)
+                .unwrap();
+            entries.push(Entry { name, data });
+            pos = data_start + compressed_size;
+        }
+        entries
+    }
+
+    #[test]
+    fn many_small_files_round_trip() {
+        let mut par_zip = ParZip::builder(vec![]).build();
+        let files: Vec<(String, Vec<u8>)> = (0..20)
+            .map(|i| (format!("file_{i}.txt"), format!("contents of file {i}\n").into_bytes()))
+            .collect();
+        for (name, data) in &files {
+            par_zip.add_file(name, Cursor::new(data.clone())).unwrap();
+        }
+        let archive = par_zip.finish().unwrap();
+
+        let entries = read_entries(&archive);
+        assert_eq!(entries.len(), files.len());
+        for (entry, (name, data)) in entries.iter().zip(&files) {
+            assert_eq!(&entry.name, name);
+            assert_eq!(&entry.data, data);
+        }
+    }
+
+    #[test]
+    fn large_entry_is_split_into_chunks_and_round_trips() {
+        let mut par_zip = ParZip::builder(vec![]).build();
+        let data: Vec<u8> = (0..SPLIT_THRESHOLD + 1).map(|i| (i % 251) as u8).collect();
+        par_zip.add_file("big.bin", Cursor::new(data.clone())).unwrap();
+        let archive = par_zip.finish().unwrap();
+
+        let entries = read_entries(&archive);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].data, data);
+    }
+}